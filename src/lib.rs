@@ -1,11 +1,37 @@
 use cosmwasm_std::{
-    entry_point, to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
+    entry_point, from_binary, to_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo,
+    Order, Response, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
-use cw_storage_plus::{Item, Map};
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_storage_plus::{Bound, Item, Map};
 use serde::{Deserialize, Serialize};
 
 // ===== STATE =====
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvoiceStatus {
+    Open,
+    PartiallyPaid,
+    Paid,
+    Cancelled,
+    Expired,
+    Forwarded,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum CancelReason {
+    Duplicate,
+    Fraudulent,
+    IssuedInError,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PaymentAsset {
+    Native { denom: String },
+    Cw20 { contract: Addr },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Invoice {
     pub id: u64,
@@ -14,17 +40,49 @@ pub struct Invoice {
     pub amount: Uint128,
     pub description: String,
     pub due_date: u64,
-    pub is_paid: bool,
+    pub status: InvoiceStatus,
+    pub cancel_reason: Option<CancelReason>,
+    pub cancel_note: Option<String>,
+    pub is_split: bool,
+    pub asset: PaymentAsset,
+    pub payout_address: Addr,
+    pub collected: Uint128,
+    pub amount_paid: Uint128,
+    pub invoice_number: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PayerShare {
+    pub owed: Uint128,
+    pub paid: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NumberingScheme {
+    pub prefix: String,
+    pub suffix: String,
+    pub pad_width: u32,
+    pub last_number: u64,
 }
 
 pub const NEXT_INVOICE_ID: Item<u64> = Item::new("next_invoice_id");
 pub const INVOICES: Map<u64, Invoice> = Map::new("invoices");
 pub const USER_INVOICES: Map<Addr, Vec<u64>> = Map::new("user_invoices");
+pub const PAYER_SHARES: Map<(u64, Addr), PayerShare> = Map::new("payer_shares");
+pub const ADMIN: Item<Addr> = Item::new("admin");
+pub const NUMBERING_SCHEME: Item<NumberingScheme> = Item::new("numbering_scheme");
+pub const INVOICE_NUMBERS: Map<String, u64> = Map::new("invoice_numbers");
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+const MAX_PAD_WIDTH: u32 = 32;
 
 // ===== MESSAGES =====
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct InstantiateMsg {}
+pub struct InstantiateMsg {
+    pub admin: Option<String>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ExecuteMsg {
@@ -33,14 +91,59 @@ pub enum ExecuteMsg {
         amount: Uint128,
         description: String,
         due_date: u64,
+        asset: PaymentAsset,
+        payout_address: Option<String>,
     },
     PayInvoice { invoice_id: u64 },
+    Receive(Cw20ReceiveMsg),
+    ExpireInvoice { invoice_id: u64 },
+    CancelInvoice {
+        invoice_id: u64,
+        reason: CancelReason,
+        note: Option<String>,
+    },
+    CreateSplitInvoice {
+        payers: Vec<String>,
+        total_amount: Uint128,
+        description: String,
+        due_date: u64,
+        weights: Option<Vec<Uint128>>,
+        denom: String,
+        payout_address: Option<String>,
+    },
+    PayShare { invoice_id: u64 },
+    Withdraw { invoice_id: u64 },
+    SetNumberingScheme {
+        prefix: String,
+        suffix: String,
+        pad_width: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Cw20HookMsg {
+    PayInvoice { invoice_id: u64 },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum QueryMsg {
     GetInvoice { invoice_id: u64 },
     GetUserInvoices { user: String },
+    ListInvoicesByStatus {
+        status: InvoiceStatus,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    GetSplitStatus { invoice_id: u64 },
+    GetBalance { invoice_id: u64 },
+    GetInvoiceByNumber { invoice_number: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PayerShareInfo {
+    pub payer: Addr,
+    pub owed: Uint128,
+    pub paid: bool,
 }
 
 // ===== INSTANTIATE =====
@@ -49,10 +152,27 @@ pub enum QueryMsg {
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
-    _msg: InstantiateMsg,
+    info: MessageInfo,
+    msg: InstantiateMsg,
 ) -> StdResult<Response> {
     NEXT_INVOICE_ID.save(deps.storage, &1)?; // Initialize the invoice ID counter
+
+    let admin = match msg.admin {
+        Some(admin) => deps.api.addr_validate(&admin)?,
+        None => info.sender,
+    };
+    ADMIN.save(deps.storage, &admin)?;
+
+    NUMBERING_SCHEME.save(
+        deps.storage,
+        &NumberingScheme {
+            prefix: "INV-".to_string(),
+            suffix: String::new(),
+            pad_width: 4,
+            last_number: 0,
+        },
+    )?;
+
     Ok(Response::new().add_attribute("action", "instantiate"))
 }
 
@@ -61,7 +181,7 @@ pub fn instantiate(
 #[entry_point]
 pub fn execute(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> StdResult<Response> {
@@ -71,19 +191,77 @@ pub fn execute(
             amount,
             description,
             due_date,
-        } => execute_create_invoice(deps, info, recipient, amount, description, due_date),
-        ExecuteMsg::PayInvoice { invoice_id } => execute_pay_invoice(deps, info, invoice_id),
+            asset,
+            payout_address,
+        } => execute_create_invoice(
+            deps,
+            info,
+            CreateInvoiceParams {
+                recipient,
+                amount,
+                description,
+                due_date,
+                asset,
+                payout_address,
+            },
+        ),
+        ExecuteMsg::PayInvoice { invoice_id } => execute_pay_invoice(deps, env, info, invoice_id),
+        ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
+        ExecuteMsg::ExpireInvoice { invoice_id } => execute_expire_invoice(deps, env, invoice_id),
+        ExecuteMsg::CancelInvoice {
+            invoice_id,
+            reason,
+            note,
+        } => execute_cancel_invoice(deps, info, invoice_id, reason, note),
+        ExecuteMsg::CreateSplitInvoice {
+            payers,
+            total_amount,
+            description,
+            due_date,
+            weights,
+            denom,
+            payout_address,
+        } => execute_create_split_invoice(
+            deps,
+            info,
+            CreateSplitInvoiceParams {
+                payers,
+                total_amount,
+                description,
+                due_date,
+                weights,
+                denom,
+                payout_address,
+            },
+        ),
+        ExecuteMsg::PayShare { invoice_id } => execute_pay_share(deps, info, invoice_id),
+        ExecuteMsg::Withdraw { invoice_id } => execute_withdraw(deps, info, invoice_id),
+        ExecuteMsg::SetNumberingScheme {
+            prefix,
+            suffix,
+            pad_width,
+        } => execute_set_numbering_scheme(deps, info, prefix, suffix, pad_width),
     }
 }
 
-fn execute_create_invoice(
-    deps: DepsMut,
-    info: MessageInfo,
+struct CreateInvoiceParams {
     recipient: String,
     amount: Uint128,
     description: String,
     due_date: u64,
-) -> StdResult<Response> {
+    asset: PaymentAsset,
+    payout_address: Option<String>,
+}
+
+fn execute_create_invoice(deps: DepsMut, info: MessageInfo, params: CreateInvoiceParams) -> StdResult<Response> {
+    let CreateInvoiceParams {
+        recipient,
+        amount,
+        description,
+        due_date,
+        asset,
+        payout_address,
+    } = params;
     let recipient_addr = deps.api.addr_validate(&recipient)?;
     if info.sender == recipient_addr {
         return Err(StdError::generic_err("Cannot create invoice for yourself"));
@@ -91,9 +269,14 @@ fn execute_create_invoice(
     if amount.is_zero() {
         return Err(StdError::generic_err("Amount must be greater than zero"));
     }
+    let payout_addr = match payout_address {
+        Some(addr) => deps.api.addr_validate(&addr)?,
+        None => info.sender.clone(),
+    };
 
     let id = NEXT_INVOICE_ID.load(deps.storage)?;
     NEXT_INVOICE_ID.save(deps.storage, &(id + 1))?;
+    let invoice_number = next_invoice_number(deps.storage, id)?;
 
     let invoice = Invoice {
         id,
@@ -102,7 +285,15 @@ fn execute_create_invoice(
         amount,
         description,
         due_date,
-        is_paid: false,
+        status: InvoiceStatus::Open,
+        cancel_reason: None,
+        cancel_note: None,
+        is_split: false,
+        asset,
+        payout_address: payout_addr,
+        collected: Uint128::zero(),
+        amount_paid: Uint128::zero(),
+        invoice_number,
     };
 
     INVOICES.save(deps.storage, id, &invoice)?;
@@ -118,27 +309,455 @@ fn execute_create_invoice(
         .add_attribute("invoice_id", id.to_string()))
 }
 
-fn execute_pay_invoice(deps: DepsMut, info: MessageInfo, invoice_id: u64) -> StdResult<Response> {
+fn execute_pay_invoice(deps: DepsMut, env: Env, info: MessageInfo, invoice_id: u64) -> StdResult<Response> {
     let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+    if invoice.is_split {
+        return Err(StdError::generic_err("Split invoices are paid via PayShare, not PayInvoice"));
+    }
     if invoice.recipient != info.sender {
         return Err(StdError::generic_err("Only the recipient can pay this invoice"));
     }
-    if invoice.is_paid {
-        return Err(StdError::generic_err("Invoice is already paid"));
+    if invoice.status != InvoiceStatus::Open && invoice.status != InvoiceStatus::PartiallyPaid {
+        return Err(StdError::generic_err("Invoice is not open for payment"));
+    }
+    if env.block.time.seconds() > invoice.due_date {
+        return Err(StdError::generic_err(
+            "Invoice is past its due date and must be expired before it can be reasoned about",
+        ));
     }
-    if info.funds.len() != 1 || info.funds[0].amount != invoice.amount {
+    let denom = match &invoice.asset {
+        PaymentAsset::Native { denom } => denom,
+        PaymentAsset::Cw20 { .. } => {
+            return Err(StdError::generic_err(
+                "This invoice is settled via cw20 Receive, not native funds",
+            ))
+        }
+    };
+    let remaining = invoice.amount - invoice.amount_paid;
+    if info.funds.len() != 1 || info.funds[0].denom != *denom {
         return Err(StdError::generic_err("Incorrect payment amount"));
     }
+    let payment = info.funds[0].amount;
+    if payment.is_zero() || payment > remaining {
+        return Err(StdError::generic_err("Payment must be nonzero and not exceed the remaining balance"));
+    }
 
-    invoice.is_paid = true;
+    invoice.amount_paid += payment;
+    invoice.collected = invoice.amount_paid;
+    invoice.status = if invoice.amount_paid == invoice.amount {
+        InvoiceStatus::Paid
+    } else {
+        InvoiceStatus::PartiallyPaid
+    };
+    let new_remaining = invoice.amount - invoice.amount_paid;
     INVOICES.save(deps.storage, invoice_id, &invoice)?;
 
     Ok(Response::new()
         .add_attribute("action", "pay_invoice")
         .add_attribute("payer", info.sender.to_string())
+        .add_attribute("invoice_id", invoice_id.to_string())
+        .add_attribute("amount_paid", invoice.amount_paid.to_string())
+        .add_attribute("remaining", new_remaining.to_string()))
+}
+
+fn execute_receive(deps: DepsMut, env: Env, info: MessageInfo, wrapper: Cw20ReceiveMsg) -> StdResult<Response> {
+    let hook_msg: Cw20HookMsg = from_binary(&wrapper.msg)?;
+
+    match hook_msg {
+        Cw20HookMsg::PayInvoice { invoice_id } => {
+            let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+            if invoice.is_split {
+                return Err(StdError::generic_err("Split invoices are paid via PayShare, not Receive"));
+            }
+            if invoice.status != InvoiceStatus::Open && invoice.status != InvoiceStatus::PartiallyPaid {
+                return Err(StdError::generic_err("Invoice is not open for payment"));
+            }
+            if env.block.time.seconds() > invoice.due_date {
+                return Err(StdError::generic_err(
+                    "Invoice is past its due date and must be expired before it can be reasoned about",
+                ));
+            }
+            let contract = match &invoice.asset {
+                PaymentAsset::Cw20 { contract } => contract,
+                PaymentAsset::Native { .. } => {
+                    return Err(StdError::generic_err("This invoice is settled via native funds, not cw20"))
+                }
+            };
+            if info.sender != *contract {
+                return Err(StdError::generic_err("Unexpected cw20 token contract"));
+            }
+            let remaining = invoice.amount - invoice.amount_paid;
+            if wrapper.amount.is_zero() || wrapper.amount > remaining {
+                return Err(StdError::generic_err("Payment must be nonzero and not exceed the remaining balance"));
+            }
+            let payer = deps.api.addr_validate(&wrapper.sender)?;
+            if invoice.recipient != payer {
+                return Err(StdError::generic_err("Only the recipient can pay this invoice"));
+            }
+
+            invoice.amount_paid += wrapper.amount;
+            invoice.collected = invoice.amount_paid;
+            invoice.status = if invoice.amount_paid == invoice.amount {
+                InvoiceStatus::Paid
+            } else {
+                InvoiceStatus::PartiallyPaid
+            };
+            let new_remaining = invoice.amount - invoice.amount_paid;
+            INVOICES.save(deps.storage, invoice_id, &invoice)?;
+
+            Ok(Response::new()
+                .add_attribute("action", "pay_invoice")
+                .add_attribute("payer", payer.to_string())
+                .add_attribute("invoice_id", invoice_id.to_string())
+                .add_attribute("amount_paid", invoice.amount_paid.to_string())
+                .add_attribute("remaining", new_remaining.to_string()))
+        }
+    }
+}
+
+fn refund_message(invoice: &Invoice) -> StdResult<CosmosMsg> {
+    Ok(match &invoice.asset {
+        PaymentAsset::Native { denom } => CosmosMsg::Bank(BankMsg::Send {
+            to_address: invoice.recipient.to_string(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount: invoice.amount_paid,
+            }],
+        }),
+        PaymentAsset::Cw20 { contract } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: invoice.recipient.to_string(),
+                amount: invoice.amount_paid,
+            })?,
+            funds: vec![],
+        }),
+    })
+}
+
+fn execute_expire_invoice(deps: DepsMut, env: Env, invoice_id: u64) -> StdResult<Response> {
+    let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+    if invoice.status != InvoiceStatus::Open && invoice.status != InvoiceStatus::PartiallyPaid {
+        return Err(StdError::generic_err("Only an open or partially paid invoice can be expired"));
+    }
+    if invoice.is_split && !invoice.collected.is_zero() {
+        return Err(StdError::generic_err(
+            "Cannot expire a split invoice that already has collected payer shares",
+        ));
+    }
+    if env.block.time.seconds() <= invoice.due_date {
+        return Err(StdError::generic_err("Invoice is not past its due date yet"));
+    }
+
+    let refund = if invoice.amount_paid.is_zero() {
+        None
+    } else {
+        Some(refund_message(&invoice)?)
+    };
+
+    invoice.status = InvoiceStatus::Expired;
+    INVOICES.save(deps.storage, invoice_id, &invoice)?;
+
+    let mut response = Response::new()
+        .add_attribute("action", "expire_invoice")
+        .add_attribute("invoice_id", invoice_id.to_string());
+    if let Some(refund) = refund {
+        response = response.add_message(refund);
+    }
+    Ok(response)
+}
+
+fn execute_cancel_invoice(
+    deps: DepsMut,
+    info: MessageInfo,
+    invoice_id: u64,
+    reason: CancelReason,
+    note: Option<String>,
+) -> StdResult<Response> {
+    let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+    if invoice.issuer != info.sender {
+        return Err(StdError::generic_err("Only the issuer can cancel this invoice"));
+    }
+    if invoice.status != InvoiceStatus::Open && invoice.status != InvoiceStatus::PartiallyPaid {
+        return Err(StdError::generic_err("Only an open or partially paid invoice can be cancelled"));
+    }
+    if invoice.is_split && !invoice.collected.is_zero() {
+        return Err(StdError::generic_err(
+            "Cannot cancel a split invoice that already has collected payer shares",
+        ));
+    }
+
+    let refund = if invoice.amount_paid.is_zero() {
+        None
+    } else {
+        Some(refund_message(&invoice)?)
+    };
+
+    let reason_attr = format!("{:?}", reason);
+    invoice.status = InvoiceStatus::Cancelled;
+    invoice.cancel_reason = Some(reason);
+    invoice.cancel_note = note;
+    INVOICES.save(deps.storage, invoice_id, &invoice)?;
+
+    let mut response = Response::new()
+        .add_attribute("action", "cancel_invoice")
+        .add_attribute("invoice_id", invoice_id.to_string())
+        .add_attribute("reason", reason_attr);
+    if let Some(refund) = refund {
+        response = response.add_message(refund);
+    }
+    Ok(response)
+}
+
+struct CreateSplitInvoiceParams {
+    payers: Vec<String>,
+    total_amount: Uint128,
+    description: String,
+    due_date: u64,
+    weights: Option<Vec<Uint128>>,
+    denom: String,
+    payout_address: Option<String>,
+}
+
+fn execute_create_split_invoice(
+    deps: DepsMut,
+    info: MessageInfo,
+    params: CreateSplitInvoiceParams,
+) -> StdResult<Response> {
+    let CreateSplitInvoiceParams {
+        payers,
+        total_amount,
+        description,
+        due_date,
+        weights,
+        denom,
+        payout_address,
+    } = params;
+    if payers.is_empty() {
+        return Err(StdError::generic_err("A split invoice needs at least one payer"));
+    }
+    if total_amount.is_zero() {
+        return Err(StdError::generic_err("Amount must be greater than zero"));
+    }
+    let payout_addr = match payout_address {
+        Some(addr) => deps.api.addr_validate(&addr)?,
+        None => info.sender.clone(),
+    };
+
+    let payer_addrs: Vec<Addr> = payers
+        .iter()
+        .map(|p| deps.api.addr_validate(p))
+        .collect::<StdResult<_>>()?;
+
+    let mut seen_payers = std::collections::HashSet::new();
+    for payer_addr in &payer_addrs {
+        if !seen_payers.insert(payer_addr.clone()) {
+            return Err(StdError::generic_err("Duplicate payer address in split invoice"));
+        }
+    }
+
+    let shares = compute_shares(total_amount, payer_addrs.len(), weights)?;
+
+    let id = NEXT_INVOICE_ID.load(deps.storage)?;
+    NEXT_INVOICE_ID.save(deps.storage, &(id + 1))?;
+    let invoice_number = next_invoice_number(deps.storage, id)?;
+
+    let invoice = Invoice {
+        id,
+        issuer: info.sender.clone(),
+        recipient: info.sender.clone(),
+        amount: total_amount,
+        description,
+        due_date,
+        status: InvoiceStatus::Open,
+        cancel_reason: None,
+        cancel_note: None,
+        is_split: true,
+        asset: PaymentAsset::Native { denom: denom.clone() },
+        payout_address: payout_addr,
+        collected: Uint128::zero(),
+        amount_paid: Uint128::zero(),
+        invoice_number,
+    };
+    INVOICES.save(deps.storage, id, &invoice)?;
+
+    for (payer_addr, owed) in payer_addrs.iter().zip(shares.iter()) {
+        PAYER_SHARES.save(
+            deps.storage,
+            (id, payer_addr.clone()),
+            &PayerShare {
+                owed: *owed,
+                paid: false,
+            },
+        )?;
+    }
+
+    let mut user_invoices = USER_INVOICES.may_load(deps.storage, info.sender.clone())?.unwrap_or_default();
+    user_invoices.push(id);
+    USER_INVOICES.save(deps.storage, info.sender.clone(), &user_invoices)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "create_split_invoice")
+        .add_attribute("issuer", info.sender.to_string())
+        .add_attribute("invoice_id", id.to_string())
+        .add_attribute("payers", payers.join(",")))
+}
+
+fn compute_shares(total_amount: Uint128, num_payers: usize, weights: Option<Vec<Uint128>>) -> StdResult<Vec<Uint128>> {
+    let weights = match weights {
+        Some(w) => {
+            if w.len() != num_payers {
+                return Err(StdError::generic_err("Number of weights must match number of payers"));
+            }
+            w
+        }
+        None => vec![Uint128::one(); num_payers],
+    };
+
+    let total_weight: Uint128 = weights.iter().fold(Uint128::zero(), |acc, w| acc + *w);
+    if total_weight.is_zero() {
+        return Err(StdError::generic_err("Weights must sum to more than zero"));
+    }
+
+    let mut shares: Vec<Uint128> = weights
+        .iter()
+        .map(|w| total_amount.multiply_ratio(*w, total_weight))
+        .collect();
+
+    let allocated: Uint128 = shares.iter().fold(Uint128::zero(), |acc, s| acc + *s);
+    let remainder = total_amount - allocated;
+    shares[0] += remainder;
+
+    Ok(shares)
+}
+
+fn execute_pay_share(deps: DepsMut, info: MessageInfo, invoice_id: u64) -> StdResult<Response> {
+    let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+    if !invoice.is_split {
+        return Err(StdError::generic_err("Invoice is not a split invoice"));
+    }
+    if invoice.status != InvoiceStatus::Open {
+        return Err(StdError::generic_err("Invoice is not open for payment"));
+    }
+
+    let mut share = PAYER_SHARES
+        .load(deps.storage, (invoice_id, info.sender.clone()))
+        .map_err(|_| StdError::generic_err("Sender is not a payer on this invoice"))?;
+    if share.paid {
+        return Err(StdError::generic_err("Share is already paid"));
+    }
+    let denom = match &invoice.asset {
+        PaymentAsset::Native { denom } => denom,
+        PaymentAsset::Cw20 { .. } => {
+            return Err(StdError::generic_err("This invoice is settled via cw20, not native funds"))
+        }
+    };
+    if info.funds.len() != 1 || info.funds[0].denom != *denom || info.funds[0].amount != share.owed {
+        return Err(StdError::generic_err("Incorrect payment amount"));
+    }
+
+    share.paid = true;
+    PAYER_SHARES.save(deps.storage, (invoice_id, info.sender.clone()), &share)?;
+
+    invoice.collected += share.owed;
+
+    let all_paid = PAYER_SHARES
+        .prefix(invoice_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter_map(|item| item.ok())
+        .all(|(_, share)| share.paid);
+
+    if all_paid {
+        invoice.status = InvoiceStatus::Paid;
+    }
+    INVOICES.save(deps.storage, invoice_id, &invoice)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "pay_share")
+        .add_attribute("payer", info.sender.to_string())
         .add_attribute("invoice_id", invoice_id.to_string()))
 }
 
+fn execute_withdraw(deps: DepsMut, info: MessageInfo, invoice_id: u64) -> StdResult<Response> {
+    let mut invoice = INVOICES.load(deps.storage, invoice_id)?;
+    if invoice.issuer != info.sender {
+        return Err(StdError::generic_err("Only the issuer can withdraw this invoice"));
+    }
+    if invoice.status != InvoiceStatus::Paid {
+        return Err(StdError::generic_err("Only a fully paid invoice can be withdrawn"));
+    }
+
+    let transfer_msg: CosmosMsg = match &invoice.asset {
+        PaymentAsset::Native { denom } => CosmosMsg::Bank(BankMsg::Send {
+            to_address: invoice.payout_address.to_string(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount: invoice.collected,
+            }],
+        }),
+        PaymentAsset::Cw20 { contract } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: invoice.payout_address.to_string(),
+                amount: invoice.collected,
+            })?,
+            funds: vec![],
+        }),
+    };
+
+    invoice.status = InvoiceStatus::Forwarded;
+    INVOICES.save(deps.storage, invoice_id, &invoice)?;
+
+    Ok(Response::new()
+        .add_message(transfer_msg)
+        .add_attribute("action", "withdraw")
+        .add_attribute("invoice_id", invoice_id.to_string())
+        .add_attribute("amount", invoice.collected.to_string())
+        .add_attribute("payout_address", invoice.payout_address.to_string()))
+}
+
+fn next_invoice_number(storage: &mut dyn Storage, id: u64) -> StdResult<String> {
+    let mut scheme = NUMBERING_SCHEME.load(storage)?;
+    scheme.last_number += 1;
+    let number = format!(
+        "{}{:0width$}{}",
+        scheme.prefix,
+        scheme.last_number,
+        scheme.suffix,
+        width = scheme.pad_width as usize
+    );
+    NUMBERING_SCHEME.save(storage, &scheme)?;
+    INVOICE_NUMBERS.save(storage, number.clone(), &id)?;
+    Ok(number)
+}
+
+fn execute_set_numbering_scheme(
+    deps: DepsMut,
+    info: MessageInfo,
+    prefix: String,
+    suffix: String,
+    pad_width: u32,
+) -> StdResult<Response> {
+    let admin = ADMIN.load(deps.storage)?;
+    if info.sender != admin {
+        return Err(StdError::generic_err("Only the contract admin can set the numbering scheme"));
+    }
+    if pad_width > MAX_PAD_WIDTH {
+        return Err(StdError::generic_err(format!(
+            "pad_width must be at most {}",
+            MAX_PAD_WIDTH
+        )));
+    }
+
+    let mut scheme = NUMBERING_SCHEME.load(deps.storage)?;
+    scheme.prefix = prefix;
+    scheme.suffix = suffix;
+    scheme.pad_width = pad_width;
+    NUMBERING_SCHEME.save(deps.storage, &scheme)?;
+
+    Ok(Response::new().add_attribute("action", "set_numbering_scheme"))
+}
+
 // ===== QUERY =====
 
 #[entry_point]
@@ -146,6 +765,16 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetInvoice { invoice_id } => to_binary(&query_invoice(deps, invoice_id)?),
         QueryMsg::GetUserInvoices { user } => to_binary(&query_user_invoices(deps, user)?),
+        QueryMsg::ListInvoicesByStatus {
+            status,
+            start_after,
+            limit,
+        } => to_binary(&query_invoices_by_status(deps, status, start_after, limit)?),
+        QueryMsg::GetSplitStatus { invoice_id } => to_binary(&query_split_status(deps, invoice_id)?),
+        QueryMsg::GetBalance { invoice_id } => to_binary(&query_balance(deps, invoice_id)?),
+        QueryMsg::GetInvoiceByNumber { invoice_number } => {
+            to_binary(&query_invoice_by_number(deps, invoice_number)?)
+        }
     }
 }
 
@@ -164,3 +793,395 @@ fn query_user_invoices(deps: Deps, user: String) -> StdResult<Vec<Invoice>> {
         .collect();
     Ok(invoices)
 }
+
+fn query_invoices_by_status(
+    deps: Deps,
+    status: InvoiceStatus,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<Invoice>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+
+    let invoices: Vec<Invoice> = INVOICES
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter_map(|item| item.ok())
+        .map(|(_, invoice)| invoice)
+        .filter(|invoice| invoice.status == status)
+        .take(limit)
+        .collect();
+
+    Ok(invoices)
+}
+
+fn query_split_status(deps: Deps, invoice_id: u64) -> StdResult<Vec<PayerShareInfo>> {
+    let shares: Vec<PayerShareInfo> = PAYER_SHARES
+        .prefix(invoice_id)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter_map(|item| item.ok())
+        .map(|(payer, share)| PayerShareInfo {
+            payer,
+            owed: share.owed,
+            paid: share.paid,
+        })
+        .collect();
+
+    Ok(shares)
+}
+
+fn query_balance(deps: Deps, invoice_id: u64) -> StdResult<Uint128> {
+    let invoice = INVOICES.load(deps.storage, invoice_id)?;
+    Ok(invoice.amount - invoice.amount_paid)
+}
+
+fn query_invoice_by_number(deps: Deps, invoice_number: String) -> StdResult<Invoice> {
+    let id = INVOICE_NUMBERS.load(deps.storage, invoice_number)?;
+    INVOICES.load(deps.storage, id)
+}
+
+// ===== TESTS =====
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+
+    fn create_native_invoice(deps: DepsMut, amount: u128, due_date: u64) {
+        execute(
+            deps,
+            mock_env(),
+            mock_info("issuer", &[]),
+            ExecuteMsg::CreateInvoice {
+                recipient: "payer".to_string(),
+                amount: Uint128::new(amount),
+                description: "test invoice".to_string(),
+                due_date,
+                asset: PaymentAsset::Native { denom: "uusd".to_string() },
+                payout_address: None,
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn open_invoice_expires_after_due_date() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let too_early = execute(deps.as_mut(), mock_env(), mock_info("issuer", &[]), ExecuteMsg::ExpireInvoice { invoice_id: 1 })
+            .unwrap_err();
+        assert!(matches!(too_early, StdError::GenericErr { .. }));
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2000);
+        execute(deps.as_mut(), env, mock_info("issuer", &[]), ExecuteMsg::ExpireInvoice { invoice_id: 1 }).unwrap();
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Expired);
+    }
+
+    #[test]
+    fn list_invoices_by_status_paginates() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        for _ in 0..5 {
+            create_native_invoice(deps.as_mut(), 100, due_date);
+        }
+
+        let first_page = query_invoices_by_status(deps.as_ref(), InvoiceStatus::Open, None, Some(2)).unwrap();
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(first_page[0].id, 1);
+        assert_eq!(first_page[1].id, 2);
+
+        let second_page =
+            query_invoices_by_status(deps.as_ref(), InvoiceStatus::Open, Some(first_page[1].id), Some(2)).unwrap();
+        assert_eq!(second_page.len(), 2);
+        assert_eq!(second_page[0].id, 3);
+        assert_eq!(second_page[1].id, 4);
+    }
+
+    #[test]
+    fn split_invoice_settles_once_all_shares_paid() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("issuer", &[]),
+            ExecuteMsg::CreateSplitInvoice {
+                payers: vec!["aaaa".to_string(), "bbbb".to_string(), "cccc".to_string()],
+                total_amount: Uint128::new(100),
+                description: "dinner".to_string(),
+                due_date: mock_env().block.time.seconds() + 1000,
+                weights: None,
+                denom: "uusd".to_string(),
+                payout_address: None,
+            },
+        )
+        .unwrap();
+
+        let shares = query_split_status(deps.as_ref(), 1).unwrap();
+        let total_owed: Uint128 = shares.iter().fold(Uint128::zero(), |acc, s| acc + s.owed);
+        assert_eq!(total_owed, Uint128::new(100));
+
+        for (payer, owed) in [("aaaa", 34u128), ("bbbb", 33), ("cccc", 33)] {
+            let pay_info = mock_info(payer, &cosmwasm_std::coins(owed, "uusd"));
+            execute(deps.as_mut(), mock_env(), pay_info, ExecuteMsg::PayShare { invoice_id: 1 }).unwrap();
+        }
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(invoice.collected, Uint128::new(100));
+    }
+
+    #[test]
+    fn duplicate_payer_is_rejected() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("issuer", &[]),
+            ExecuteMsg::CreateSplitInvoice {
+                payers: vec!["aaaa".to_string(), "aaaa".to_string()],
+                total_amount: Uint128::new(100),
+                description: "dinner".to_string(),
+                due_date: mock_env().block.time.seconds() + 1000,
+                weights: None,
+                denom: "uusd".to_string(),
+                payout_address: None,
+            },
+        )
+        .unwrap_err();
+        match err {
+            StdError::GenericErr { msg } => assert!(msg.contains("Duplicate payer")),
+            other => panic!("expected a duplicate payer error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cw20_receive_settles_invoice() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("issuer", &[]),
+            ExecuteMsg::CreateInvoice {
+                recipient: "payer".to_string(),
+                amount: Uint128::new(100),
+                description: "test invoice".to_string(),
+                due_date,
+                asset: PaymentAsset::Cw20 { contract: Addr::unchecked("cw20contract") },
+                payout_address: None,
+            },
+        )
+        .unwrap();
+
+        let hook_msg = to_binary(&Cw20HookMsg::PayInvoice { invoice_id: 1 }).unwrap();
+        let receive_info = mock_info("cw20contract", &[]);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            receive_info,
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "payer".to_string(),
+                amount: Uint128::new(100),
+                msg: hook_msg,
+            }),
+        )
+        .unwrap();
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(invoice.collected, Uint128::new(100));
+    }
+
+    #[test]
+    fn cw20_receive_rejects_payment_past_due_date() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("issuer", &[]),
+            ExecuteMsg::CreateInvoice {
+                recipient: "payer".to_string(),
+                amount: Uint128::new(100),
+                description: "test invoice".to_string(),
+                due_date,
+                asset: PaymentAsset::Cw20 { contract: Addr::unchecked("cw20contract") },
+                payout_address: None,
+            },
+        )
+        .unwrap();
+
+        let hook_msg = to_binary(&Cw20HookMsg::PayInvoice { invoice_id: 1 }).unwrap();
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(2000);
+        let err = execute(
+            deps.as_mut(),
+            env,
+            mock_info("cw20contract", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "payer".to_string(),
+                amount: Uint128::new(100),
+                msg: hook_msg,
+            }),
+        )
+        .unwrap_err();
+        assert!(matches!(err, StdError::GenericErr { .. }));
+    }
+
+    #[test]
+    fn pay_and_withdraw_native_invoice() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let pay_info = mock_info("payer", &cosmwasm_std::coins(100, "uusd"));
+        execute(deps.as_mut(), mock_env(), pay_info, ExecuteMsg::PayInvoice { invoice_id: 1 }).unwrap();
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(invoice.collected, Uint128::new(100));
+
+        let withdraw_info = mock_info("issuer", &[]);
+        let res = execute(deps.as_mut(), mock_env(), withdraw_info, ExecuteMsg::Withdraw { invoice_id: 1 }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Forwarded);
+    }
+
+    #[test]
+    fn withdraw_before_fully_paid_is_rejected() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("issuer", &[]), ExecuteMsg::Withdraw { invoice_id: 1 })
+            .unwrap_err();
+        assert!(matches!(err, StdError::GenericErr { .. }));
+    }
+
+    #[test]
+    fn installment_payments_track_balance() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let first_payment = mock_info("payer", &cosmwasm_std::coins(40, "uusd"));
+        execute(deps.as_mut(), mock_env(), first_payment, ExecuteMsg::PayInvoice { invoice_id: 1 }).unwrap();
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::PartiallyPaid);
+        assert_eq!(query_balance(deps.as_ref(), 1).unwrap(), Uint128::new(60));
+
+        let second_payment = mock_info("payer", &cosmwasm_std::coins(60, "uusd"));
+        execute(deps.as_mut(), mock_env(), second_payment, ExecuteMsg::PayInvoice { invoice_id: 1 }).unwrap();
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+        assert_eq!(query_balance(deps.as_ref(), 1).unwrap(), Uint128::zero());
+    }
+
+    #[test]
+    fn overpayment_is_rejected() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let pay_info = mock_info("payer", &cosmwasm_std::coins(150, "uusd"));
+        let err = execute(deps.as_mut(), mock_env(), pay_info, ExecuteMsg::PayInvoice { invoice_id: 1 }).unwrap_err();
+        assert!(matches!(err, StdError::GenericErr { .. }));
+    }
+
+    #[test]
+    fn cancel_partially_paid_invoice_refunds_payer() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let pay_info = mock_info("payer", &cosmwasm_std::coins(40, "uusd"));
+        execute(deps.as_mut(), mock_env(), pay_info, ExecuteMsg::PayInvoice { invoice_id: 1 }).unwrap();
+
+        let cancel_info = mock_info("issuer", &[]);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            cancel_info,
+            ExecuteMsg::CancelInvoice {
+                invoice_id: 1,
+                reason: CancelReason::IssuedInError,
+                note: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.status, InvoiceStatus::Cancelled);
+    }
+
+    #[test]
+    fn invoice_number_format_and_lookup() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), InstantiateMsg { admin: None }).unwrap();
+
+        let due_date = mock_env().block.time.seconds() + 1000;
+        create_native_invoice(deps.as_mut(), 100, due_date);
+
+        let invoice = query_invoice(deps.as_ref(), 1).unwrap();
+        assert_eq!(invoice.invoice_number, "INV-0001");
+
+        let looked_up = query_invoice_by_number(deps.as_ref(), "INV-0001".to_string()).unwrap();
+        assert_eq!(looked_up.id, 1);
+    }
+
+    #[test]
+    fn pad_width_over_limit_is_rejected() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            InstantiateMsg { admin: Some("admin".to_string()) },
+        )
+        .unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            ExecuteMsg::SetNumberingScheme {
+                prefix: "INV-".to_string(),
+                suffix: String::new(),
+                pad_width: 999,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, StdError::GenericErr { .. }));
+    }
+}